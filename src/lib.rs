@@ -1,13 +1,40 @@
 //! # icub3d Sudoku Solver
 //!
 //! `icub3d_sudoku_solver` is simple utility for solving a sudoku
-//! board using backtracking.
+//! board using backtracking. Boards aren't limited to the classic
+//! 9x9 grid; any perfect-square order from 4 to 25 (4, 9, 16, or
+//! 25) is supported.
 
 #![crate_name = "icub3d_sudoku_solver"]
 
+use std::collections::HashSet;
+
 use failure::{bail, Error};
+use rand::seq::SliceRandom;
+use rand::Rng;
 use regex::Regex;
 
+/// The technique used to deduce a [`Step`] in [`Board::solve_logic`].
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Technique {
+    /// The cell has exactly one remaining candidate.
+    NakedSingle,
+    /// A candidate value can only go in one cell within some row,
+    /// column, or box, even though that cell has other candidates.
+    HiddenSingle,
+}
+
+/// A single deduction made by [`Board::solve_logic`].
+#[derive(Debug, PartialEq)]
+pub struct Step {
+    /// The position in the grid (`row * order + column`) that was assigned.
+    pub pos: usize,
+    /// The value assigned to `pos`.
+    pub value: u8,
+    /// The technique that justified the assignment.
+    pub technique: Technique,
+}
+
 /// A representation of a sudoku board.
 ///
 /// # Example
@@ -20,65 +47,319 @@ use regex::Regex;
 ///
 /// assert_eq!(board.solve(), true);
 /// assert_eq!(board.to_string(), "1 2 9 | 4 3 7 | 5 8 6\n8 6 7 | 2 5 1 | 4 9 3\n5 4 3 | 8 9 6 | 1 2 7\n------+-------+------\n7 9 5 | 3 6 2 | 8 1 4\n2 8 1 | 5 7 4 | 3 6 9\n4 3 6 | 1 8 9 | 2 7 5\n------+-------+------\n9 1 4 | 7 2 5 | 6 3 8\n6 5 8 | 9 1 3 | 7 4 2\n3 7 2 | 6 4 8 | 9 5 1\n");
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub struct Board {
+    /// The order of the board, e.g. 9 for a standard 9x9 board.
+    order: usize,
+    /// The size of a box (the square root of `order`), e.g. 3 for a
+    /// standard 9x9 board.
+    box_size: usize,
     grid: Vec<u8>,
+    /// The constraints a placement must satisfy. Always starts with
+    /// `RowRule`, `ColumnRule`, and `BoxRule`; [`Board::add_rule`]
+    /// can attach variant rules such as `DiagonalRule` or `CageRule`.
+    rules: Vec<Box<dyn Rule>>,
+}
+
+/// A constraint a candidate placement must satisfy.
+///
+/// `is_satisfied` is asked whether `n` could go at `pos` in `grid`,
+/// regardless of what (if anything) is currently stored there; it
+/// must ignore `pos` itself when scanning `grid` for conflicts.
+pub trait Rule: std::fmt::Debug {
+    fn is_satisfied(&self, grid: &[u8], pos: usize, n: u8, order: usize) -> bool;
+}
+
+/// Every row must contain each value at most once.
+#[derive(Debug)]
+pub struct RowRule;
+
+impl Rule for RowRule {
+    fn is_satisfied(&self, grid: &[u8], pos: usize, n: u8, order: usize) -> bool {
+        let y = pos / order;
+        (0..order).all(|x| {
+            let p = y * order + x;
+            p == pos || grid[p] != n
+        })
+    }
+}
+
+/// Every column must contain each value at most once.
+#[derive(Debug)]
+pub struct ColumnRule;
+
+impl Rule for ColumnRule {
+    fn is_satisfied(&self, grid: &[u8], pos: usize, n: u8, order: usize) -> bool {
+        let x = pos % order;
+        (0..order).all(|y| {
+            let p = y * order + x;
+            p == pos || grid[p] != n
+        })
+    }
+}
+
+/// Every `sqrt(order)` x `sqrt(order)` box must contain each value at
+/// most once.
+#[derive(Debug)]
+pub struct BoxRule;
+
+impl Rule for BoxRule {
+    fn is_satisfied(&self, grid: &[u8], pos: usize, n: u8, order: usize) -> bool {
+        let box_size = (order as f64).sqrt().round() as usize;
+        let x = pos % order;
+        let y = pos / order;
+        let x0 = (x / box_size) * box_size;
+        let y0 = (y / box_size) * box_size;
+        for dy in 0..box_size {
+            for dx in 0..box_size {
+                let p = (y0 + dy) * order + (x0 + dx);
+                if p != pos && grid[p] == n {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// X-Sudoku: the two main diagonals must each contain every value at
+/// most once.
+#[derive(Debug)]
+pub struct DiagonalRule;
+
+impl Rule for DiagonalRule {
+    fn is_satisfied(&self, grid: &[u8], pos: usize, n: u8, order: usize) -> bool {
+        let x = pos % order;
+        let y = pos / order;
+
+        if x == y {
+            for i in 0..order {
+                let p = i * order + i;
+                if p != pos && grid[p] == n {
+                    return false;
+                }
+            }
+        }
+
+        if x + y == order - 1 {
+            for i in 0..order {
+                let p = i * order + (order - 1 - i);
+                if p != pos && grid[p] == n {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// Killer Sudoku: the listed cells must contain no repeated value and
+/// sum to exactly `sum` once all of them are filled.
+#[derive(Debug)]
+pub struct CageRule {
+    pub cells: Vec<usize>,
+    pub sum: u8,
+}
+
+impl Rule for CageRule {
+    fn is_satisfied(&self, grid: &[u8], pos: usize, n: u8, _order: usize) -> bool {
+        if !self.cells.contains(&pos) {
+            return true;
+        }
+
+        let mut total = n as u32;
+        let mut filled = 1;
+        for &c in &self.cells {
+            if c == pos {
+                continue;
+            }
+            let v = grid[c];
+            if v == 0 {
+                continue;
+            }
+            if v == n {
+                return false;
+            }
+            total += v as u32;
+            filled += 1;
+        }
+
+        if total > self.sum as u32 {
+            return false;
+        }
+        filled < self.cells.len() || total == self.sum as u32
+    }
 }
 
 impl Board {
-    /// Create a new board. Will fail if the string isn't exactly 81
-    /// characters long. '.', '_', ' ', and '0' can be used for empty
-    /// spaces.
+    /// Create a new board. The length of the string determines its
+    /// order: 16 characters makes a 4x4 board, 81 a 9x9 board, 256 a
+    /// 16x16 board, and 625 a 25x25 board. '.', '_', ' ', and '0' can
+    /// be used for empty spaces, '1'-'9' for values up to nine, and
+    /// 'A'-'P' for values above nine. Embedded newlines, carriage
+    /// returns, and tabs are stripped before the length is checked,
+    /// so a board can be pasted in across multiple lines.
     pub fn new(s: String) -> Result<Board, Error> {
-        let re = Regex::new(r"^[ 0-9._]*$")?;
-        if s.len() != 81 {
-            bail!("string must be exactly 81 characters");
-        } else if !re.is_match(&s) {
-            bail!("string must contain only digits (and _, ' ', or . for zero, empty)");
+        let s: String = s
+            .chars()
+            .filter(|c| !matches!(c, '\n' | '\r' | '\t'))
+            .collect();
+
+        let re = Regex::new(r"^[ 0-9A-P._]*$")?;
+        if !re.is_match(&s) {
+            bail!("string must contain only digits, A-P, and _, ' ', or . for zero, empty");
         }
 
-        let mut b = Board { grid: Vec::new() };
+        let len = s.len();
+        let order = (len as f64).sqrt().round() as usize;
+        if order * order != len {
+            bail!("string length must be a perfect square (e.g. 16, 81, 256, 625)");
+        }
+        let box_size = (order as f64).sqrt().round() as usize;
+        if box_size * box_size != order || !matches!(order, 4 | 9 | 16 | 25) {
+            bail!("board order must be one of 4, 9, 16, or 25");
+        }
+
+        let mut grid = Vec::with_capacity(len);
         for c in s.chars() {
-            if c == '.' || c == '_' || c == ' ' {
-                b.grid.push(0);
-            } else {
-                b.grid.push(c.to_string().parse()?);
-            }
+            grid.push(Board::char_to_value(c, order)?);
         }
-        Ok(b)
+        Ok(Board {
+            order,
+            box_size,
+            grid,
+            rules: vec![Box::new(RowRule), Box::new(ColumnRule), Box::new(BoxRule)],
+        })
     }
 
-    fn valid(&self, p: usize, n: u8) -> bool {
-        let x = p % 9;
-        let y = p / 9;
-        // Check the column and row but exclude the position being checked.
-        for i in 0..9 {
-            if i != x && self.grid[y * 9 + i] == n {
-                return false;
+    /// Attach an additional rule (e.g. `DiagonalRule` or `CageRule`)
+    /// that candidate placements must also satisfy.
+    pub fn add_rule(&mut self, rule: Box<dyn Rule>) {
+        self.rules.push(rule);
+    }
+
+    /// Create a new board from its coordinate-list representation: a
+    /// `rows,cols` header line followed by one `row,col,value` line
+    /// per cell worth recording, all 0-based, with `value` 0 meaning
+    /// empty. Cells that aren't listed default to empty.
+    pub fn from_coords(s: &str) -> Result<Board, Error> {
+        let mut lines = s.lines().map(str::trim).filter(|l| !l.is_empty());
+
+        let header = match lines.next() {
+            Some(header) => header,
+            None => bail!("missing \"rows,cols\" header line"),
+        };
+        let mut header = header.splitn(2, ',');
+        let rows: usize = header
+            .next()
+            .ok_or_else(|| failure::format_err!("missing row count in header"))?
+            .parse()?;
+        let cols: usize = header
+            .next()
+            .ok_or_else(|| failure::format_err!("missing column count in header"))?
+            .parse()?;
+        if rows != cols {
+            bail!("boards must be square; got {}x{}", rows, cols);
+        }
+
+        let mut board = Board::new("0".repeat(rows * cols))?;
+        for line in lines {
+            let mut parts = line.splitn(3, ',');
+            let row: usize = parts
+                .next()
+                .ok_or_else(|| failure::format_err!("missing row in \"{}\"", line))?
+                .parse()?;
+            let col: usize = parts
+                .next()
+                .ok_or_else(|| failure::format_err!("missing column in \"{}\"", line))?
+                .parse()?;
+            let value: u8 = parts
+                .next()
+                .ok_or_else(|| failure::format_err!("missing value in \"{}\"", line))?
+                .parse()?;
+            if row >= board.order || col >= board.order {
+                bail!("position ({}, {}) is out of bounds", row, col);
             }
-            if i != y && self.grid[i * 9 + x] == n {
-                return false;
+            if value as usize > board.order {
+                bail!("value {} is out of range for a board of order {}", value, board.order);
             }
+            board.grid[row * board.order + col] = value;
         }
 
-        // Check the containing box.
-        let x0 = (x / 3) * 3;
-        let y0 = (y / 3) * 3;
-        for dx in 0..3 {
-            for dy in 0..3 {
-                // Ignore the position being checked.
-                if (y0 + dy) == y && (x0 + dx) == x {
-                    continue;
-                }
-                if self.grid[(y0 + dy) * 9 + x0 + dx] == n {
-                    return false;
-                }
+        Ok(board)
+    }
+
+    /// Emit the coordinate-list representation read by
+    /// [`Board::from_coords`]: a `rows,cols` header followed by one
+    /// `row,col,value` line per filled cell.
+    pub fn to_coords(&self) -> String {
+        let mut s = format!("{},{}\n", self.order, self.order);
+        for (p, &v) in self.grid.iter().enumerate() {
+            if v != 0 {
+                s.push_str(&format!("{},{},{}\n", p / self.order, p % self.order, v));
             }
         }
-        return true;
+        s
+    }
+
+    /// Emit the single-line form read by [`Board::new`]: one
+    /// character per cell, in row-major order, using `.` for empty
+    /// cells.
+    pub fn to_compact_string(&self) -> String {
+        self.grid
+            .iter()
+            .map(|&v| {
+                if v == 0 {
+                    '.'.to_string()
+                } else {
+                    Board::value_to_string(v)
+                }
+            })
+            .collect()
+    }
+
+    /// Convert a single input character into its cell value, rejecting
+    /// anything outside `1..=order` (0 for blanks is always allowed).
+    fn char_to_value(c: char, order: usize) -> Result<u8, Error> {
+        let value = match c {
+            '.' | '_' | ' ' => 0,
+            '0'..='9' => c.to_string().parse()?,
+            'A'..='P' => c as u8 - b'A' + 10,
+            _ => bail!("unexpected character '{}'", c),
+        };
+        if value as usize > order {
+            bail!("value {} is out of range for a board of order {}", value, order);
+        }
+        Ok(value)
     }
 
-    fn solved(&self) -> bool {
+    /// Convert a cell value back into its display character.
+    fn value_to_string(n: u8) -> String {
+        if n <= 9 {
+            n.to_string()
+        } else {
+            ((b'A' + (n - 10)) as char).to_string()
+        }
+    }
+
+    /// Whether `n` could be placed at `p` without violating any
+    /// attached rule.
+    fn valid(&self, p: usize, n: u8) -> bool {
+        self.valid_in(&self.grid, p, n)
+    }
+
+    /// Whether `n` could be placed at `p` in `grid` (which need not
+    /// be `self.grid`) without violating any attached rule.
+    fn valid_in(&self, grid: &[u8], p: usize, n: u8) -> bool {
+        self.rules
+            .iter()
+            .all(|r| r.is_satisfied(grid, p, n, self.order))
+    }
+
+    /// Whether every cell is filled with a value consistent with
+    /// every attached rule.
+    pub fn solved(&self) -> bool {
         for (p, n) in self.grid.iter().enumerate() {
             if *n == 0 || !self.valid(p, *n) {
                 return false;
@@ -89,65 +370,419 @@ impl Board {
 
     /// Solve the board. Returns true on success and false if no
     /// solution was found.
+    ///
+    /// With only the standard row/column/box rules attached, this
+    /// walks the grid with backtracking using a `u32` candidate
+    /// bitmask per row, column, and box (bit `v - 1` set means `v` is
+    /// already used in that unit) rather than rescanning with `valid`
+    /// on every attempt, and always branches on the empty cell with
+    /// the fewest remaining candidates (the Minimum Remaining Values
+    /// heuristic), pruning immediately if any empty cell has none
+    /// left. Any additional rule attached with [`Board::add_rule`]
+    /// can't be folded into those masks, so it falls back to plain
+    /// `valid`-checked backtracking instead.
     pub fn solve(&mut self) -> bool {
-        self.solve_helper(self.next_unsolved(0))
+        if self.rules.len() > 3 {
+            let p = self.next_unsolved(0);
+            return self.solve_generic(p);
+        }
+
+        let mut row_masks = vec![0u32; self.order];
+        let mut col_masks = vec![0u32; self.order];
+        let mut box_masks = vec![0u32; self.order];
+
+        for p in 0..self.grid.len() {
+            let n = self.grid[p];
+            if n != 0 {
+                let bit = 1u32 << (n - 1);
+                let (x, y, b) = self.coords(p);
+                row_masks[y] |= bit;
+                col_masks[x] |= bit;
+                box_masks[b] |= bit;
+            }
+        }
+
+        self.solve_masked(&mut row_masks, &mut col_masks, &mut box_masks)
     }
 
     fn next_unsolved(&self, p: usize) -> usize {
-        for i in p..81 {
-            if self.grid[i] == 0 {
-                return i;
+        (p..self.grid.len())
+            .find(|&i| self.grid[i] == 0)
+            .unwrap_or(self.grid.len())
+    }
+
+    /// Plain backtracking over `valid`, used when rules beyond the
+    /// standard row/column/box set are attached.
+    fn solve_generic(&mut self, p: usize) -> bool {
+        if p == self.grid.len() {
+            return true;
+        }
+
+        for n in 1..=(self.order as u8) {
+            if self.valid(p, n) {
+                self.grid[p] = n;
+                if self.solve_generic(self.next_unsolved(p + 1)) {
+                    return true;
+                }
             }
         }
-        return 81;
+
+        self.grid[p] = 0;
+        false
     }
 
-    fn solve_helper(&mut self, p: usize) -> bool {
-        // Check to see if we have reached the end.
-        if p == 81 {
-            return self.solved();
+    /// Count how many solutions this board has, stopping as soon as
+    /// `limit` is reached. The board is left unchanged; the search
+    /// runs over a scratch copy of the grid.
+    pub fn count_solutions(&self, limit: usize) -> usize {
+        let mut grid = self.grid.clone();
+        let mut count = 0;
+        self.count_solutions_in(&mut grid, 0, limit, &mut count);
+        count
+    }
+
+    fn count_solutions_in(&self, grid: &mut Vec<u8>, p: usize, limit: usize, count: &mut usize) {
+        if *count >= limit {
+            return;
+        }
+
+        let p = match (p..grid.len()).find(|&i| grid[i] == 0) {
+            Some(p) => p,
+            None => {
+                *count += 1;
+                return;
+            }
+        };
+
+        for n in 1..=(self.order as u8) {
+            if *count >= limit {
+                return;
+            }
+            if self.valid_in(grid, p, n) {
+                grid[p] = n;
+                self.count_solutions_in(grid, p + 1, limit, count);
+                grid[p] = 0;
+            }
         }
+    }
+
+    /// Whether this board has exactly one solution.
+    pub fn has_unique_solution(&self) -> bool {
+        self.count_solutions(2) == 1
+    }
+
+    /// Generate a new, uniquely-solvable standard 9x9 puzzle with
+    /// `clues` given cells. A complete grid is filled in randomly,
+    /// then cells are cleared one at a time, in random order,
+    /// checking [`Board::has_unique_solution`] after each removal;
+    /// a removal that would introduce a second solution is undone.
+    /// Stops once `clues` is reached or no more cells can be removed.
+    pub fn generate(clues: usize) -> Board {
+        let mut rng = rand::thread_rng();
+
+        let mut board = Board::new("0".repeat(81)).unwrap();
+        board.fill_randomly(&mut rng);
+
+        let mut positions: Vec<usize> = (0..board.grid.len()).collect();
+        positions.shuffle(&mut rng);
+
+        let mut remaining = board.grid.len();
+        for p in positions {
+            if remaining <= clues {
+                break;
+            }
 
-        // We are at an unsolved square. Let's try different values.
-        for n in 1..10 {
-            // Try all valid positions.
+            let saved = board.grid[p];
+            board.grid[p] = 0;
+            if board.has_unique_solution() {
+                remaining -= 1;
+            } else {
+                board.grid[p] = saved;
+            }
+        }
+
+        board
+    }
+
+    /// Fill every empty cell with a randomly-ordered backtracking
+    /// search, producing a randomly shuffled complete grid.
+    fn fill_randomly(&mut self, rng: &mut impl Rng) -> bool {
+        let p = match (0..self.grid.len()).find(|&i| self.grid[i] == 0) {
+            Some(p) => p,
+            None => return true,
+        };
+
+        let mut values: Vec<u8> = (1..=self.order as u8).collect();
+        values.shuffle(rng);
+        for n in values {
             if self.valid(p, n) {
-                // Check to see if this was a solution.
                 self.grid[p] = n;
-
-                if self.solve_helper(self.next_unsolved(p + 1)) {
+                if self.fill_randomly(rng) {
                     return true;
                 }
+                self.grid[p] = 0;
             }
         }
 
-        // If we've tried them all, this one isn't the solution.
-        self.grid[p] = 0;
-        return false;
+        false
+    }
+
+    /// The column, row, and box index for a position.
+    fn coords(&self, p: usize) -> (usize, usize, usize) {
+        let x = p % self.order;
+        let y = p / self.order;
+        let b = (y / self.box_size) * self.box_size + (x / self.box_size);
+        (x, y, b)
+    }
+
+    /// Backtracking search over the MRV-chosen empty cell, using the
+    /// row/column/box masks to test and apply candidates in O(1).
+    fn solve_masked(
+        &mut self,
+        row_masks: &mut [u32],
+        col_masks: &mut [u32],
+        box_masks: &mut [u32],
+    ) -> bool {
+        let full_mask = (1u32 << self.order) - 1;
+
+        let mut chosen: Option<(usize, usize, usize, usize, u32)> = None;
+        for p in 0..self.grid.len() {
+            if self.grid[p] != 0 {
+                continue;
+            }
+            let (x, y, b) = self.coords(p);
+            let legal = full_mask & !(row_masks[y] | col_masks[x] | box_masks[b]);
+            if legal == 0 {
+                return false;
+            }
+            let count = legal.count_ones();
+            if chosen.is_none_or(|(_, _, _, _, prev)| count < prev.count_ones()) {
+                chosen = Some((p, x, y, b, legal));
+                if count == 1 {
+                    break;
+                }
+            }
+        }
+
+        let (p, x, y, b, legal) = match chosen {
+            Some(c) => c,
+            None => return true,
+        };
+
+        let mut remaining = legal;
+        while remaining != 0 {
+            let bit = remaining & remaining.wrapping_neg();
+            remaining &= remaining - 1;
+            let n = bit.trailing_zeros() as u8 + 1;
+
+            self.grid[p] = n;
+            row_masks[y] |= bit;
+            col_masks[x] |= bit;
+            box_masks[b] |= bit;
+
+            if self.solve_masked(row_masks, col_masks, box_masks) {
+                return true;
+            }
+
+            self.grid[p] = 0;
+            row_masks[y] &= !bit;
+            col_masks[x] &= !bit;
+            box_masks[b] &= !bit;
+        }
+
+        false
+    }
+
+    /// Solve the board using human-style logic rather than
+    /// backtracking, returning the ordered deductions it made. Each
+    /// empty cell is tracked as a bitmask of remaining candidates
+    /// (bit `v - 1` set means `v` is still possible); the solver
+    /// repeatedly applies two rules until neither makes progress:
+    /// a **naked single**, where a cell has exactly one candidate,
+    /// and a **hidden single**, where a candidate appears in only
+    /// one cell's set within some row, column, or box. Check
+    /// `self.solved()` afterwards to see whether logic alone was
+    /// enough; if not, the remaining empty cells need `solve` (or a
+    /// more advanced technique) to finish. Candidates always account
+    /// for every attached rule (see `assign`), so a recorded step is
+    /// never a deduction that a `DiagonalRule` or `CageRule` would
+    /// reject.
+    pub fn solve_logic(&mut self) -> Vec<Step> {
+        let mut steps = Vec::new();
+        let mut candidates = self.compute_all_candidates();
+
+        loop {
+            let mut progress = false;
+
+            for p in 0..self.grid.len() {
+                if self.grid[p] != 0 || candidates[p].count_ones() != 1 {
+                    continue;
+                }
+                let value = candidates[p].trailing_zeros() as u8 + 1;
+                self.assign(p, value, &mut candidates);
+                steps.push(Step {
+                    pos: p,
+                    value,
+                    technique: Technique::NakedSingle,
+                });
+                progress = true;
+            }
+            if progress {
+                continue;
+            }
+
+            for unit in self.units() {
+                for v in 1..=(self.order as u8) {
+                    let bit = 1u32 << (v - 1);
+                    let mut cells = unit
+                        .iter()
+                        .filter(|&&p| self.grid[p] == 0 && candidates[p] & bit != 0);
+                    if let (Some(&p), None) = (cells.next(), cells.next()) {
+                        self.assign(p, v, &mut candidates);
+                        steps.push(Step {
+                            pos: p,
+                            value: v,
+                            technique: Technique::HiddenSingle,
+                        });
+                        progress = true;
+                    }
+                }
+            }
+
+            if !progress {
+                break;
+            }
+        }
+
+        steps
+    }
+
+    /// The candidate bitmask for every cell: 0 for already-filled
+    /// cells, otherwise the values 1..=order that `valid` allows,
+    /// i.e. not excluded by any attached rule.
+    fn compute_all_candidates(&self) -> Vec<u32> {
+        (0..self.grid.len())
+            .map(|p| {
+                if self.grid[p] != 0 {
+                    0
+                } else {
+                    let mut mask = 0u32;
+                    for v in 1..=(self.order as u8) {
+                        if self.valid(p, v) {
+                            mask |= 1 << (v - 1);
+                        }
+                    }
+                    mask
+                }
+            })
+            .collect()
+    }
+
+    /// Assign `value` at `p` and update every other cell's
+    /// candidates to match. With only the standard row/column/box
+    /// rules attached, this just clears `value`'s bit from `p`'s
+    /// peers (cells sharing a row, column, or box with `p`), since
+    /// those are the only cells a placement at `p` can affect. Any
+    /// additional rule attached with [`Board::add_rule`] (e.g.
+    /// `DiagonalRule` or `CageRule`) can introduce peers outside that
+    /// set, so in that case every remaining cell's candidates are
+    /// recomputed from scratch via `valid` instead.
+    fn assign(&mut self, p: usize, value: u8, candidates: &mut [u32]) {
+        self.grid[p] = value;
+        candidates[p] = 0;
+
+        if self.rules.len() > 3 {
+            candidates.copy_from_slice(&self.compute_all_candidates());
+            return;
+        }
+
+        let bit = 1u32 << (value - 1);
+        for peer in self.peers(p) {
+            candidates[peer] &= !bit;
+        }
+    }
+
+    /// The other positions sharing a row, column, or box with `p`.
+    fn peers(&self, p: usize) -> HashSet<usize> {
+        let x = p % self.order;
+        let y = p / self.order;
+
+        let mut peers = HashSet::new();
+        for i in 0..self.order {
+            peers.insert(y * self.order + i);
+            peers.insert(i * self.order + x);
+        }
+
+        let x0 = (x / self.box_size) * self.box_size;
+        let y0 = (y / self.box_size) * self.box_size;
+        for dy in 0..self.box_size {
+            for dx in 0..self.box_size {
+                peers.insert((y0 + dy) * self.order + (x0 + dx));
+            }
+        }
+
+        peers.remove(&p);
+        peers
+    }
+
+    /// Every row, column, and box as a list of positions.
+    fn units(&self) -> Vec<Vec<usize>> {
+        let mut units = Vec::with_capacity(self.order * 3);
+
+        for y in 0..self.order {
+            units.push((0..self.order).map(|x| y * self.order + x).collect());
+        }
+        for x in 0..self.order {
+            units.push((0..self.order).map(|y| y * self.order + x).collect());
+        }
+        for by in (0..self.order).step_by(self.box_size) {
+            for bx in (0..self.order).step_by(self.box_size) {
+                let mut cells = Vec::with_capacity(self.order);
+                for dy in 0..self.box_size {
+                    for dx in 0..self.box_size {
+                        cells.push((by + dy) * self.order + (bx + dx));
+                    }
+                }
+                units.push(cells);
+            }
+        }
+
+        units
+    }
+
+    /// Build the string for a single row, e.g. "1 2 9 | 4 3 7 | 5 8 6".
+    fn row_string(&self, y: usize) -> String {
+        let mut groups = Vec::with_capacity(self.box_size);
+        for gx in 0..self.box_size {
+            let mut cells = Vec::with_capacity(self.box_size);
+            for i in 0..self.box_size {
+                let x = gx * self.box_size + i;
+                cells.push(Board::value_to_string(self.grid[y * self.order + x]));
+            }
+            groups.push(cells.join(" "));
+        }
+        groups.join(" | ")
+    }
+
+    /// Build the separator line between box rows, matching the width
+    /// of `row_string` for any order.
+    fn separator_string(&self) -> String {
+        self.row_string(0)
+            .chars()
+            .map(|c| if c == '|' { '+' } else { '-' })
+            .collect()
     }
 }
 
-impl std::string::ToString for Board {
-    fn to_string(&self) -> String {
-        let mut s = String::new();
-        for y in 0..9 {
-            if y % 3 == 0 && y != 0 {
-                s.push_str(&format!("------+-------+------\n"));
+impl std::fmt::Display for Board {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for y in 0..self.order {
+            if y % self.box_size == 0 && y != 0 {
+                writeln!(f, "{}", self.separator_string())?;
             }
-            s.push_str(&format!(
-                "{} {} {} | {} {} {} | {} {} {}\n",
-                self.grid[y * 9 + 0],
-                self.grid[y * 9 + 1],
-                self.grid[y * 9 + 2],
-                self.grid[y * 9 + 3],
-                self.grid[y * 9 + 4],
-                self.grid[y * 9 + 5],
-                self.grid[y * 9 + 6],
-                self.grid[y * 9 + 7],
-                self.grid[y * 9 + 8]
-            ));
+            writeln!(f, "{}", self.row_string(y))?;
         }
-        s
+        Ok(())
     }
 }
 
@@ -206,6 +841,23 @@ mod tests {
         assert_eq!(board.solve(), true);
     }
 
+    #[test]
+    fn solve_masked_matches_generic() {
+        // A puzzle hard enough that a naive, unordered search explores
+        // far more dead ends than the MRV/bitmask path; both should
+        // still land on the same (unique) solution.
+        let s = "800000000003600000070090200050007000000045700000100030001000068008500010090000400";
+        let mut masked = Board::new(s.to_string()).unwrap();
+        let mut generic = Board::new(s.to_string()).unwrap();
+
+        assert!(masked.solve());
+        let p = generic.next_unsolved(0);
+        assert!(generic.solve_generic(p));
+
+        assert_eq!(masked.grid, generic.grid);
+        assert!(masked.solved());
+    }
+
     #[test]
     fn to_string() {
         let mut board = Board::new(
@@ -245,17 +897,214 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = r#"string must be exactly 81 characters"#)]
+    #[should_panic(expected = r#"perfect square"#)]
     fn board_new_too_short() {
         Board::new("24000078930800001600180002".to_string()).unwrap();
     }
 
     #[test]
-    #[should_panic(expected = r#"string must be exactly 81 characters"#)]
+    #[should_panic(expected = r#"perfect square"#)]
     fn board_new_too_long() {
         Board::new(
             "240000789308000016000000001800000000000000000000000000000000000000000000000000000000000002"
                 .to_string(),
         ).unwrap();
     }
+
+    #[test]
+    #[should_panic(expected = r#"board order must be one of 4, 9, 16, or 25"#)]
+    fn board_new_rejects_non_documented_order() {
+        // 1296 = 36 * 36, and 36 is itself a perfect square (6 * 6),
+        // but 36 isn't one of the orders the A-P encoding supports.
+        Board::new("0".repeat(1296)).unwrap();
+    }
+
+    #[test]
+    fn board_new_rejects_value_above_order() {
+        assert!(Board::new("9".repeat(16)).is_err());
+    }
+
+    #[test]
+    fn board_new_4x4() {
+        let board = Board::new("1234341221434321".to_string()).unwrap();
+        assert_eq!(board.order, 4);
+        assert_eq!(board.box_size, 2);
+    }
+
+    #[test]
+    fn board_new_16x16_with_letters() {
+        let s = "1 2 3 4 5 6 7 8 9 A B C D E F G\
+G F E D C B A 9 8 7 6 5 4 3 2 1\
+2 1 4 3 6 5 8 7 A 9 C B E D G F\
+F G D E B C 9 A 7 8 5 6 3 4 1 2\
+3 4 1 2 7 8 5 6 B A G F 9 C D E\
+E D G F A 9 C B 6 5 8 7 2 1 4 3\
+4 3 2 1 8 7 6 5 C B F E A G 3 4\
+5 6 7 8 1 2 3 4 D C E G B A F 9\
+6 5 8 7 2 1 4 3 E D G F C B A 9\
+9 A B C D E F G 1 2 3 4 5 6 7 8\
+7 8 5 6 3 4 1 2 F E D G A 9 C B\
+8 7 6 5 4 3 2 1 G F E D 9 A B C\
+A 9 C B E D G F 2 1 4 3 6 5 8 7\
+B C F E G A D 3 4 1 2 7 8 5 6 9\
+C B A 9 F G E D 5 6 7 8 1 2 3 4\
+D E F G 9 C B A 3 4 1 2 8 7 6 5"
+            .replace(' ', "");
+        let board = Board::new(s).unwrap();
+        assert_eq!(board.order, 16);
+        assert_eq!(board.box_size, 4);
+        assert_eq!(board.grid[9], 10);
+        assert_eq!(board.grid[15], 16);
+    }
+
+    #[test]
+    fn solve_logic_already_solved() {
+        let mut board = Board::new(
+            "129437586867251493543896127795362814281574369436189275914725638658913742372648951"
+                .to_string(),
+        )
+        .unwrap();
+        assert_eq!(board.solve_logic(), vec![]);
+        assert!(board.solved());
+    }
+
+    #[test]
+    fn solve_logic_naked_single() {
+        let mut board = Board::new(
+            "029437586867251493543896127795362814281574369436189275914725638658913742372648951"
+                .to_string(),
+        )
+        .unwrap();
+        let steps = board.solve_logic();
+        assert_eq!(
+            steps,
+            vec![Step {
+                pos: 0,
+                value: 1,
+                technique: Technique::NakedSingle,
+            }]
+        );
+        assert!(board.solved());
+    }
+
+    #[test]
+    fn solve_logic_hidden_single() {
+        let mut board = Board::new("0010100000010000".to_string()).unwrap();
+        let steps = board.solve_logic();
+        assert_eq!(steps[0].pos, 13);
+        assert_eq!(steps[0].value, 1);
+        assert_eq!(steps[0].technique, Technique::HiddenSingle);
+    }
+
+    #[test]
+    fn solve_logic_respects_diagonal_rule() {
+        // Without re-checking `valid` across every attached rule on
+        // each assignment, naked singles on the main diagonal used to
+        // be deduced independently of each other and could collide.
+        let mut board = Board::new("4000200400020010".to_string()).unwrap();
+        board.add_rule(Box::new(DiagonalRule));
+        board.solve_logic();
+
+        for diag in [[0, 5, 10, 15], [3, 6, 9, 12]] {
+            let mut values: Vec<u8> = diag
+                .iter()
+                .map(|&p| board.grid[p])
+                .filter(|&v| v != 0)
+                .collect();
+            let filled = values.len();
+            values.sort();
+            values.dedup();
+            assert_eq!(values.len(), filled);
+        }
+    }
+
+    #[test]
+    fn solve_with_diagonal_rule() {
+        let mut board = Board::new("0".repeat(16)).unwrap();
+        board.add_rule(Box::new(DiagonalRule));
+        assert!(board.solve());
+        assert!(board.solved());
+
+        let mut main_diag: Vec<u8> = (0..4).map(|i| board.grid[i * 4 + i]).collect();
+        main_diag.sort();
+        assert_eq!(main_diag, vec![1, 2, 3, 4]);
+
+        let mut anti_diag: Vec<u8> = (0..4).map(|i| board.grid[i * 4 + (3 - i)]).collect();
+        anti_diag.sort();
+        assert_eq!(anti_diag, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn solve_with_cage_rule() {
+        let mut board = Board::new("0".repeat(16)).unwrap();
+        board.add_rule(Box::new(CageRule {
+            cells: vec![0, 1],
+            sum: 3,
+        }));
+        assert!(board.solve());
+        assert_eq!(board.grid[0] + board.grid[1], 3);
+    }
+
+    #[test]
+    fn count_solutions_on_solved_board() {
+        let board = Board::new(
+            "129437586867251493543896127795362814281574369436189275914725638658913742372648951"
+                .to_string(),
+        )
+        .unwrap();
+        assert_eq!(board.count_solutions(2), 1);
+        assert!(board.has_unique_solution());
+    }
+
+    #[test]
+    fn count_solutions_on_empty_board() {
+        let board = Board::new("0".repeat(16)).unwrap();
+        assert_eq!(board.count_solutions(2), 2);
+        assert!(!board.has_unique_solution());
+    }
+
+    #[test]
+    fn generate_produces_unique_puzzle() {
+        let board = Board::generate(30);
+        assert_eq!(board.grid.len(), 81);
+        assert!(board.has_unique_solution());
+
+        let clue_count = board.grid.iter().filter(|&&v| v != 0).count();
+        assert!(clue_count >= 30);
+    }
+
+    #[test]
+    fn board_new_strips_embedded_whitespace() {
+        let board = Board::new("0010\n1000\n0001\n0000\t".to_string()).unwrap();
+        assert_eq!(
+            board.grid,
+            vec![0, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn from_coords_and_to_coords_round_trip() {
+        let coords = "4,4\n0,2,1\n1,0,1\n2,3,1\n";
+        let board = Board::from_coords(coords).unwrap();
+        assert_eq!(
+            board.grid,
+            vec![0, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0]
+        );
+        assert_eq!(board.to_coords(), coords);
+    }
+
+    #[test]
+    fn from_coords_rejects_value_above_order() {
+        assert!(Board::from_coords("4,4\n0,0,5\n").is_err());
+    }
+
+    #[test]
+    fn to_compact_string_reverses_new() {
+        let s = "129437586867251493543896127795362814281574369436189275914725638658913742372648951";
+        let board = Board::new(s.to_string()).unwrap();
+        assert_eq!(board.to_compact_string(), s);
+
+        let blank = Board::new("0".repeat(16)).unwrap();
+        assert_eq!(blank.to_compact_string(), ".".repeat(16));
+    }
 }